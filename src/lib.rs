@@ -1,15 +1,16 @@
 use async_std::prelude::*;
-use futures::poll;
-use inotify::{Inotify, WatchMask};
+use futures::{future, select, FutureExt};
+use inotify::{EventStream, Inotify, WatchMask};
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeSet, HashMap},
     error::Error,
     path::{Path, PathBuf},
-    task::Poll,
-    thread,
     time::Duration,
 };
 
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook_async_std::Signals;
+
 use log::{debug, error, info, warn};
 use swayipc_async::{Connection, EventType, Node, NodeType};
 
@@ -20,15 +21,62 @@ use config::Config;
 
 pub type SworkstyleError = Box<dyn Error>;
 
+/// Glyph table used to render the multiplicity marker appended to an icon when
+/// several identical windows are collapsed into a single entry (count mode).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IconCountFormat {
+    Superscript,
+    Subscript,
+    Plain,
+}
+
+impl IconCountFormat {
+    /// The per-digit glyphs for this format, indexed by the digit value.
+    fn glyphs(&self) -> [&'static str; 10] {
+        match self {
+            IconCountFormat::Superscript => {
+                ["⁰", "¹", "²", "³", "⁴", "⁵", "⁶", "⁷", "⁸", "⁹"]
+            }
+            IconCountFormat::Subscript => ["₀", "₁", "₂", "₃", "₄", "₅", "₆", "₇", "₈", "₉"],
+            IconCountFormat::Plain => ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"],
+        }
+    }
+
+    /// Render `count` as a string of this format's glyphs, e.g. `12` -> `¹²`.
+    fn render(&self, count: usize) -> String {
+        let glyphs = self.glyphs();
+        count
+            .to_string()
+            .chars()
+            .map(|digit| glyphs[digit.to_digit(10).unwrap() as usize])
+            .collect()
+    }
+}
+
 pub struct Sworkstyle {
     config: Config,
     config_path: Option<PathBuf>,
     inotify: Option<Inotify>,
     deduplicate: bool,
+    count_format: Option<IconCountFormat>,
+    format: Option<String>,
+    empty_format: Option<String>,
+    renumber_workspaces: bool,
+    /// Each workspace's name as it was at startup, keyed by node id, so the
+    /// `{name}` template placeholder can resolve to a stable base name (e.g. a
+    /// custom non-numeric name) instead of the decorated name we keep rewriting.
+    original_names: HashMap<i64, String>,
 }
 
 impl Sworkstyle {
-    pub fn new<P: AsRef<Path>>(config_path: Option<P>, deduplicate: bool) -> Sworkstyle {
+    pub fn new<P: AsRef<Path>>(
+        config_path: Option<P>,
+        deduplicate: bool,
+        count_format: Option<IconCountFormat>,
+        format: Option<String>,
+        empty_format: Option<String>,
+        renumber_workspaces: bool,
+    ) -> Result<Sworkstyle, SworkstyleError> {
         let inotify = config_path
             .as_ref()
             .map(|path| {
@@ -45,12 +93,27 @@ impl Sworkstyle {
             })
             .flatten();
 
-        Sworkstyle {
-            config: Config::new(&config_path),
+        // Count mode already collapses identical windows; letting `deduplicate`
+        // run as well would drop the repeats before they are counted, forcing
+        // every count to 1 and making count mode a silent no-op.
+        let deduplicate = if count_format.is_some() && deduplicate {
+            warn!("`count` and `deduplicate` are mutually exclusive; ignoring `deduplicate`");
+            false
+        } else {
+            deduplicate
+        };
+
+        Ok(Sworkstyle {
+            config: Config::new(&config_path)?,
             config_path: config_path.map(|p| p.as_ref().to_path_buf()),
             inotify,
             deduplicate,
-        }
+            count_format,
+            format,
+            empty_format,
+            renumber_workspaces,
+            original_names: HashMap::new(),
+        })
     }
 
     pub async fn run(&mut self) -> Result<(), SworkstyleError> {
@@ -60,40 +123,65 @@ impl Sworkstyle {
             .await?;
         let mut connection = Connection::new().await?;
 
-        let mut inotify_events_buffer = [0; 1024];
+        // Capture each workspace's name before we start decorating it, so the
+        // `{name}` placeholder can resolve to a stable base name.
+        let tree = connection.get_tree().await?;
+        let mut workspaces = vec![];
+        get_workspaces_recurse(&tree, None, &mut workspaces);
+        self.original_names = workspaces
+            .iter()
+            .filter_map(|(ws, _)| ws.name.clone().map(|name| (ws.id, name)))
+            .collect();
+
+        // Restore clean workspace names on SIGINT/SIGTERM so sway is not left
+        // with icon-decorated names like `2: ` that it will not clean up itself.
+        let mut signals = Signals::new([SIGINT, SIGTERM])?;
+
+        // Turn the config-file watcher into an async stream so the loop can
+        // sleep until either a window event, a config write, or a signal
+        // arrives, rather than polling inotify and sleeping on a timer.
+        self.inotify.take();
+        let mut config_events = watch_config(&self.config_path);
+
         loop {
-            let p = poll!(events.next());
-
-            if p.is_ready() {
-                if let Poll::Ready(Some(event)) = p {
-                    match event {
-                        Ok(_) => {
-                            if let Err(e) = self.update_workspaces(&mut connection).await {
-                                error!("Could not update workspace name: {}", e);
-                            }
-                        }
-                        Err(e) => {
-                            warn!("Connection broken, exiting: {e}");
-                            return Err(Box::new(e));
+            select! {
+                event = events.next().fuse() => match event {
+                    Some(Ok(_)) => {
+                        if let Err(e) = self.update_workspaces(&mut connection).await {
+                            error!("Could not update workspace name: {}", e);
                         }
                     }
+                    Some(Err(e)) => {
+                        warn!("Connection broken, restoring workspace names and exiting: {e}");
+                        self.reset_workspaces(&mut connection).await?;
+                        return Err(Box::new(e));
+                    }
+                    None => return Ok(()),
+                },
+
+                _ = signals.next().fuse() => {
+                    info!("Received termination signal, restoring workspace names..");
+                    self.reset_workspaces(&mut connection).await?;
+                    return Ok(());
                 }
-            }
 
-            if let Some(inotify) = &mut self.inotify {
-                if let Ok(_) = inotify.read_events(&mut inotify_events_buffer) {
-                    if let Some(config_path) = &self.config_path {
-                        info!("Detected config change, reloading config..");
-                        self.config = Config::new(&self.config_path);
-                        // Reset watcher
-                        inotify
-                            .add_watch(config_path, WatchMask::CLOSE_WRITE)
-                            .expect("Failed to watch config file");
+                _ = next_config_event(&mut config_events).fuse() => {
+                    // Coalesce the burst of writes an editor emits while saving,
+                    // then reload — keeping the previous config if the new file
+                    // does not parse, instead of swapping in a broken one.
+                    debounce_config_events(&mut config_events).await;
+                    info!("Detected config change, reloading config..");
+                    match Config::new(&self.config_path) {
+                        Ok(config) => self.config = config,
+                        Err(e) => warn!("Ignoring invalid config, keeping previous one: {e}"),
                     }
+
+                    // Re-arm the watch: an inotify watch is bound to the inode,
+                    // so an editor that saves by renaming a temp file over the
+                    // config drops it (IN_IGNORED) after the first write.
+                    config_events = watch_config(&self.config_path);
                 }
             }
-
-            thread::sleep(Duration::from_millis(100));
         }
     }
 
@@ -101,10 +189,143 @@ impl Sworkstyle {
         let tree = conn.get_tree().await?;
 
         let mut workspaces = vec![];
-        get_workspaces_recurse(&tree, &mut workspaces);
+        get_workspaces_recurse(&tree, None, &mut workspaces);
+
+        for (workspace, output) in workspaces {
+            self.update_workspace_name(conn, workspace, output.as_deref())
+                .await?;
+        }
+
+        if self.renumber_workspaces {
+            self.renumber_workspaces(conn).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Reassign workspace numbers so they are contiguous per output, e.g. turn
+    /// `1, 2, 5` on a display into `1, 2, 3` while preserving each workspace's
+    /// icon suffix and the currently focused workspace.
+    ///
+    /// Renames go through temporary unique names first so that two target
+    /// numbers colliding mid-sequence never clobber an existing workspace.
+    async fn renumber_workspaces(&self, conn: &mut Connection) -> Result<(), SworkstyleError> {
+        let tree = conn.get_tree().await?;
+
+        let mut workspaces = vec![];
+        get_workspaces_recurse(&tree, None, &mut workspaces);
+
+        // Group the workspaces by their parent output, preserving the order in
+        // which each output was first encountered.
+        let mut groups: Vec<(Option<String>, Vec<&Node>)> = vec![];
+        for (workspace, output) in workspaces {
+            match groups.iter_mut().find(|(o, _)| *o == output) {
+                Some((_, group)) => group.push(workspace),
+                None => groups.push((output, vec![workspace])),
+            }
+        }
+
+        // Compute the `old -> new` renames needed to close gaps on each output.
+        let mut renames: Vec<(String, String)> = vec![];
+        for (output, mut group) in groups {
+            group.sort_by_key(|ws| ws.num.unwrap_or(i32::MAX));
+
+            for (i, workspace) in group.iter().enumerate() {
+                let new_index = i as i32 + 1;
+
+                let num = match workspace.num {
+                    Some(num) => num,
+                    None => continue,
+                };
+
+                if num == new_index {
+                    continue;
+                }
+
+                let name = match &workspace.name {
+                    Some(name) => name,
+                    None => continue,
+                };
+
+                // Recompose the full decorated name for the new index rather
+                // than splicing it into the old string, so icons survive and
+                // any name template keeps working regardless of placeholder order.
+                let new_name = self.compose_name(workspace, output.as_deref(), new_index);
+                renames.push((name.clone(), new_name));
+            }
+        }
+
+        if renames.is_empty() {
+            return Ok(());
+        }
+
+        // Track the name each workspace currently carries so that, if any rename
+        // fails mid-sequence, we can put everything back and never leave a
+        // `__sworkstyle_renumber_tmp_*` name visible to poison the next pass.
+        let originals: Vec<&String> = renames.iter().map(|(old, _)| old).collect();
+        let mut current: Vec<String> = originals.iter().map(|old| old.to_string()).collect();
+
+        // Phase 1: move every workspace to a temporary unique name so that two
+        // target numbers colliding mid-sequence can never clobber each other.
+        for (i, (old, _)) in renames.iter().enumerate() {
+            let tmp = renumber_tmp_name(i);
+            if let Err(e) = conn
+                .run_command(format!("rename workspace \"{}\" to \"{}\"", old, tmp))
+                .await
+                .and_then(|outcomes| outcomes.into_iter().collect::<Result<Vec<()>, _>>())
+            {
+                rollback_renumber(conn, &current, &originals).await;
+                return Err(Box::new(e));
+            }
+            current[i] = tmp;
+        }
+
+        // Phase 2: move each temporary name to its final, renumbered name.
+        for (i, (_, new)) in renames.iter().enumerate() {
+            debug!("rename workspace \"{}\" to \"{}\"", current[i], new);
+
+            if let Err(e) = conn
+                .run_command(format!("rename workspace \"{}\" to \"{}\"", current[i], new))
+                .await
+                .and_then(|outcomes| outcomes.into_iter().collect::<Result<Vec<()>, _>>())
+            {
+                rollback_renumber(conn, &current, &originals).await;
+                return Err(Box::new(e));
+            }
+            current[i] = new.clone();
+        }
+
+        Ok(())
+    }
+
+    /// Walk the tree once more and rename every workspace back to a bare form,
+    /// stripping the injected icons and their `\u{202D}..\u{202C}` bidi wrappers.
+    ///
+    /// Issued on shutdown (SIGINT/SIGTERM) or when the IPC connection breaks, so
+    /// that sway does not keep decorated names around after the daemon is gone.
+    async fn reset_workspaces(&self, conn: &mut Connection) -> Result<(), SworkstyleError> {
+        let tree = conn.get_tree().await?;
+
+        let mut workspaces = vec![];
+        get_workspaces_recurse(&tree, None, &mut workspaces);
+
+        for (workspace, _output) in workspaces {
+            let name = match &workspace.name {
+                Some(name) => name,
+                None => continue,
+            };
+
+            let bare = match workspace.num {
+                Some(num) => num.to_string(),
+                None => continue,
+            };
 
-        for workspace in workspaces {
-            self.update_workspace_name(conn, workspace).await?;
+            if *name != bare {
+                debug!("rename workspace \"{}\" to \"{}\"", name, bare);
+
+                conn.run_command(format!("rename workspace \"{}\" to \"{}\"", name, bare))
+                    .await?;
+            }
         }
 
         Ok(())
@@ -114,7 +335,40 @@ impl Sworkstyle {
         &self,
         conn: &mut Connection,
         workspace: &Node,
+        output: Option<&str>,
     ) -> Result<(), SworkstyleError> {
+        let name = match &workspace.name {
+            Some(name) => name,
+            None => {
+                return Err(
+                    format!("Could not get name for workspace with id: {}", workspace.id).into(),
+                )
+            }
+        };
+
+        let index = match workspace.num {
+            Some(num) => num,
+            None => return Err(format!("Could not fetch index for: {}", name).into()),
+        };
+
+        let new_name = self.compose_name(workspace, output, index);
+
+        if *name != new_name {
+            debug!("rename workspace \"{}\" to \"{}\"", name, new_name);
+
+            conn.run_command(format!("rename workspace \"{}\" to \"{}\"", name, new_name))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Compose the decorated workspace name for `workspace` as it would appear
+    /// at position `index`, honouring the dedup/count mode and name template.
+    ///
+    /// Taking the index explicitly lets renumbering recompute a correct name for
+    /// a new number instead of doing string surgery on the already-decorated one.
+    fn compose_name(&self, workspace: &Node, output: Option<&str>, index: i32) -> String {
         let mut windows = vec![];
         get_windows(workspace, &mut windows);
 
@@ -147,7 +401,7 @@ impl Sworkstyle {
                 .collect();
         }
 
-        let mut icons: Vec<String> = window_names
+        let resolved: Vec<String> = window_names
             .into_iter()
             .map(|(exact_name, generic_name)| {
                 if let Some(exact_name) = exact_name {
@@ -164,61 +418,168 @@ impl Sworkstyle {
                         .to_string()
                 }
             })
-            // Overwrite right to left characters: https://www.unicode.org/versions/Unicode12.0.0/UnicodeStandard-12.0.pdf#G26.16327
-            .map(|icon| format!("\u{202D}{icon}\u{202C}"))
             .collect();
 
-        let name = match &workspace.name {
-            Some(name) => name,
-            None => {
-                return Err(
-                    format!("Could not get name for workspace with id: {}", workspace.id).into(),
-                )
+        // In count mode, collapse every occurrence of the same icon into a single glyph
+        // followed by a multiplicity marker (e.g. `²`) when it occurs more than
+        // once, preserving the order in which each icon was first seen.
+        let icons: Vec<String> = if let Some(format) = self.count_format {
+            let mut order: Vec<String> = vec![];
+            let mut counts: HashMap<String, usize> = HashMap::new();
+            for icon in resolved {
+                if !counts.contains_key(&icon) {
+                    order.push(icon.clone());
+                }
+                *counts.entry(icon).or_insert(0) += 1;
             }
-        };
 
-        let index = match workspace.num {
-            Some(num) => num,
-            None => return Err(format!("Could not fetch index for: {}", name).into()),
+            order
+                .into_iter()
+                .map(|icon| {
+                    let count = counts[&icon];
+                    if count > 1 {
+                        format!("{}{}", icon, format.render(count))
+                    } else {
+                        icon
+                    }
+                })
+                .collect()
+        } else {
+            resolved
         };
 
+        // Overwrite right to left characters: https://www.unicode.org/versions/Unicode12.0.0/UnicodeStandard-12.0.pdf#G26.16327
+        let mut icons: Vec<String> = icons
+            .into_iter()
+            .map(|icon| format!("\u{202D}{icon}\u{202C}"))
+            .collect();
+
         if self.deduplicate {
             icons.dedup();
         }
 
-        let mut icons = icons.join(" ");
+        let icons = icons.join(" ");
+        let output = output.unwrap_or_default();
+
+        // `{name}` resolves to the name captured at startup (falling back to the
+        // bare number), never the currently applied decorated name — otherwise
+        // the decoration would be fed back in and the name would grow each pass.
+        let fallback = index.to_string();
+        let name = self
+            .original_names
+            .get(&workspace.id)
+            .map(String::as_str)
+            .unwrap_or(&fallback);
+
         if icons.len() > 0 {
-            icons.push_str(" ")
+            match &self.format {
+                Some(template) => apply_template(template, index, &icons, name, output),
+                None => format!("{}: {} ", index, icons),
+            }
+        } else {
+            match &self.empty_format {
+                Some(template) => apply_template(template, index, &icons, name, output),
+                None => format!("{}", index),
+            }
         }
+    }
+}
 
-        let new_name = if icons.len() > 0 {
-            format!("{}: {}", index, icons)
-        } else if let Some(num) = workspace.num {
-            format!("{}", num)
-        } else {
-            error!("Could not fetch workspace num for: {:?}", workspace.name);
-            " ".to_string()
-        };
+/// Build a fresh async watcher for the config file, or `None` when no path is
+/// configured or the file does not exist. Called at startup and again after
+/// every reload, so the watch survives editors that replace the file's inode.
+fn watch_config(config_path: &Option<PathBuf>) -> Option<EventStream<[u8; 1024]>> {
+    let path = config_path.as_ref()?;
 
-        if *name != new_name {
-            debug!("rename workspace \"{}\" to \"{}\"", name, new_name);
+    if !path.exists() {
+        return None;
+    }
 
-            conn.run_command(format!("rename workspace \"{}\" to \"{}\"", name, new_name))
-                .await?;
+    let mut inotify = Inotify::init().expect("Error while initializing inotify instance");
+    inotify
+        .add_watch(path, WatchMask::CLOSE_WRITE)
+        .expect("Failed to watch config file");
+
+    inotify.into_event_stream([0; 1024]).ok()
+}
+
+/// Swallow further config-file events for a short window after the first one,
+/// so a burst of rapid `CLOSE_WRITE`s (a mid-save editor rewrite) coalesces into
+/// a single reload. Each event seen within the window restarts the timer.
+async fn debounce_config_events(stream: &mut Option<EventStream<[u8; 1024]>>) {
+    let window = Duration::from_millis(250);
+    loop {
+        select! {
+            _ = async_std::task::sleep(window).fuse() => break,
+            _ = next_config_event(stream).fuse() => continue,
         }
+    }
+}
 
-        return Ok(());
+/// Await the next config-file event, or stay pending forever when no watcher is
+/// configured, so it can participate in the `select!` without a dedicated arm.
+async fn next_config_event(stream: &mut Option<EventStream<[u8; 1024]>>) {
+    match stream {
+        Some(stream) => {
+            stream.next().await;
+        }
+        None => future::pending::<()>().await,
     }
 }
 
-fn get_workspaces_recurse<'a>(node: &'a Node, workspaces: &mut Vec<&'a Node>) {
+/// Substitute the `{index}`, `{icons}`, `{name}` and `{output}` placeholders in
+/// a user-supplied workspace-name template.
+fn apply_template(template: &str, index: i32, icons: &str, name: &str, output: &str) -> String {
+    template
+        .replace("{index}", &index.to_string())
+        .replace("{icons}", icons)
+        .replace("{name}", name)
+        .replace("{output}", output)
+}
+
+/// Best-effort restore of the original workspace names after a failed
+/// renumber, so no workspace is left stranded under a temporary name. Errors
+/// during the rollback itself are logged but otherwise ignored — there is
+/// nothing more we can do about them.
+async fn rollback_renumber(conn: &mut Connection, current: &[String], originals: &[&String]) {
+    warn!("Renumbering failed mid-run, restoring original workspace names");
+
+    for (cur, orig) in current.iter().zip(originals) {
+        if cur != *orig {
+            if let Err(e) = conn
+                .run_command(format!("rename workspace \"{}\" to \"{}\"", cur, orig))
+                .await
+            {
+                warn!("Could not restore workspace name \"{}\": {}", orig, e);
+            }
+        }
+    }
+}
+
+/// Unique placeholder name used while renumbering, so renames can go through a
+/// temporary name before landing on their final, possibly-colliding, number.
+fn renumber_tmp_name(i: usize) -> String {
+    format!("__sworkstyle_renumber_tmp_{}", i)
+}
+
+fn get_workspaces_recurse<'a>(
+    node: &'a Node,
+    output: Option<&'a str>,
+    workspaces: &mut Vec<(&'a Node, Option<String>)>,
+) {
+    let output = if node.node_type == NodeType::Output {
+        node.name.as_deref().or(output)
+    } else {
+        output
+    };
+
     if node.node_type == NodeType::Workspace && node.name != Some("__i3_scratch".to_string()) {
-        workspaces.push(node);
+        workspaces.push((node, output.map(str::to_string)));
         return;
     }
 
     for child in node.nodes.iter() {
-        get_workspaces_recurse(child, workspaces)
+        get_workspaces_recurse(child, output, workspaces)
     }
 }
 